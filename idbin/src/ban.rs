@@ -0,0 +1,261 @@
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    body::{boxed, BoxBody, Empty},
+    extract::Query,
+    http::{Response, StatusCode},
+    Extension, Form,
+};
+
+use idlib::Authorize;
+
+use serde::Deserialize;
+
+use rusqlite::{params, OptionalExtension};
+use time::OffsetDateTime;
+use tokio_rusqlite::Connection;
+
+use crate::{
+    audit::{self, AuditAction},
+    error::Error,
+    into_response,
+};
+
+/// DDL for the ban table and the `effective_users` view. Mirrors how the other
+/// admin tables are provisioned at startup; a suspended account keeps its row
+/// in `users` but drops out of `effective_users` until the ban is lifted or its
+/// `expires_at` passes.
+pub(crate) const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS user_bans ( \
+    username TEXT NOT NULL, \
+    reason TEXT NOT NULL, \
+    banned_by TEXT NOT NULL, \
+    banned_at INTEGER NOT NULL, \
+    expires_at INTEGER NULL \
+); \
+CREATE VIEW IF NOT EXISTS effective_users AS \
+SELECT u.* FROM users u \
+WHERE NOT EXISTS ( \
+    SELECT 1 FROM user_bans b \
+    WHERE b.username = u.username \
+    AND (b.expires_at IS NULL OR b.expires_at > unixepoch()) \
+);";
+
+struct BannedUser {
+    username: String,
+    reason: String,
+    banned_by: String,
+    banned_at: OffsetDateTime,
+    expires_at: Option<OffsetDateTime>,
+}
+
+#[derive(Template)]
+#[template(path = "ban.html")]
+struct BanPageTemplate {
+    users: Vec<String>,
+    bans: Vec<BannedUser>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BanParams {
+    error: Option<String>,
+}
+
+pub(crate) async fn page(
+    Authorize(_): Authorize<"users", "read">,
+    Query(params): Query<BanParams>,
+    Extension(db): Extension<Connection>,
+) -> Result<Response<BoxBody>, Error> {
+    let (users, bans) = get_users_and_bans(db).await?;
+
+    let template = BanPageTemplate {
+        users,
+        bans,
+        error: params.error,
+    };
+
+    Ok(into_response(&template, "html"))
+}
+
+#[derive(Deserialize)]
+struct DbBanInfo {
+    username: String,
+    reason: String,
+    banned_by: String,
+    banned_at: i64,
+    expires_at: Option<i64>,
+}
+
+async fn get_users_and_bans(db: Connection) -> anyhow::Result<(Vec<String>, Vec<BannedUser>)> {
+    db.call(move |conn| {
+        let mut stmt = conn
+            .prepare("SELECT username FROM users ORDER BY username")
+            .context("Failed to prepare users statement")?;
+        let users = stmt
+            .query_map(params![], |row| Ok(row.get::<_, String>(0).unwrap()))
+            .context("Failed to query users")?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()
+            .context("Failed to collect users")?;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut stmt = conn
+            .prepare(
+                "SELECT username, reason, banned_by, banned_at, expires_at \
+                FROM user_bans \
+                WHERE expires_at IS NULL OR expires_at > ?1 \
+                ORDER BY banned_at DESC",
+            )
+            .context("Failed to prepare bans statement")?;
+        let bans = stmt
+            .query_map(params![now], |row| {
+                let info = serde_rusqlite::from_row::<DbBanInfo>(row).unwrap();
+
+                Ok(BannedUser {
+                    username: info.username,
+                    reason: info.reason,
+                    banned_by: info.banned_by,
+                    banned_at: OffsetDateTime::from_unix_timestamp(info.banned_at).unwrap(),
+                    expires_at: info
+                        .expires_at
+                        .map(|at| OffsetDateTime::from_unix_timestamp(at).unwrap()),
+                })
+            })
+            .context("Failed to query bans")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to collect bans")?;
+
+        Ok((users, bans))
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DisableForm {
+    username: String,
+    reason: String,
+    /// Optional absolute unix timestamp after which the ban auto-lifts; absent
+    /// means a permanent ban.
+    expires_at: Option<i64>,
+}
+
+pub(crate) async fn disable_user(
+    Authorize(name): Authorize<"users", "write">,
+    Form(DisableForm {
+        username,
+        reason,
+        expires_at,
+    }): Form<DisableForm>,
+    Extension(db): Extension<Connection>,
+) -> Result<Response<BoxBody>, Error> {
+    let redirect = match disable_user_impl(db, name, username, reason, expires_at).await {
+        Ok(()) => "/admin/users#disabled".into(),
+        Err(e) => format!("/admin/users?error={}", urlencoding::encode(&e.to_string())),
+    };
+
+    Ok(see_other(&redirect))
+}
+
+pub(crate) async fn disable_user_impl(
+    db: Connection,
+    name: String,
+    username: String,
+    reason: String,
+    expires_at: Option<i64>,
+) -> Result<(), Error> {
+    db.call(move |conn| {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        // Only insert when the user has no currently-active ban, so
+        // re-disabling an already-banned user does not accumulate duplicate
+        // rows.
+        let inserted = conn
+            .execute(
+                "INSERT INTO user_bans (username, reason, banned_by, banned_at, expires_at) \
+                SELECT ?1, ?2, ?3, ?4, ?5 \
+                WHERE NOT EXISTS ( \
+                    SELECT 1 FROM user_bans \
+                    WHERE username = ?1 AND (expires_at IS NULL OR expires_at > ?4) \
+                )",
+                params![&username, &reason, &name, now, expires_at],
+            )
+            .context("Failed to ban user")?;
+
+        if inserted == 0 {
+            return Ok(());
+        }
+
+        audit::log(conn, AuditAction::DisableUser(username), &name)?;
+
+        Ok::<(), anyhow::Error>(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EnableForm {
+    username: String,
+}
+
+pub(crate) async fn enable_user(
+    Authorize(name): Authorize<"users", "write">,
+    Form(EnableForm { username }): Form<EnableForm>,
+    Extension(db): Extension<Connection>,
+) -> Result<Response<BoxBody>, Error> {
+    let redirect = match enable_user_impl(db, name, username).await {
+        Ok(()) => "/admin/users#enabled".into(),
+        Err(e) => format!("/admin/users?error={}", urlencoding::encode(&e.to_string())),
+    };
+
+    Ok(see_other(&redirect))
+}
+
+pub(crate) async fn enable_user_impl(
+    db: Connection,
+    name: String,
+    username: String,
+) -> Result<(), Error> {
+    db.call(move |conn| {
+        conn.execute(
+            "DELETE FROM user_bans WHERE username = ?1",
+            params![&username],
+        )
+        .context("Failed to lift ban")?;
+
+        audit::log(conn, AuditAction::EnableUser(username), &name)?;
+
+        Ok::<(), anyhow::Error>(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Whether a user is currently allowed in, consulting the `effective_users`
+/// view so timed bans auto-lift. The token mint/refresh path calls this and
+/// refuses to issue a token for a user who is absent from the view.
+pub(crate) async fn is_user_allowed(db: &Connection, username: String) -> anyhow::Result<bool> {
+    db.call(move |conn| {
+        let allowed = conn
+            .query_row(
+                "SELECT 1 FROM effective_users WHERE username = ?1",
+                params![&username],
+                |_| Ok(()),
+            )
+            .optional()
+            .context("Failed to check effective_users")?
+            .is_some();
+
+        Ok(allowed)
+    })
+    .await
+}
+
+fn see_other(location: &str) -> Response<BoxBody> {
+    Response::builder()
+        .header("Location", location)
+        .status(StatusCode::SEE_OTHER)
+        .body(boxed(Empty::new()))
+        .unwrap()
+}
@@ -13,7 +13,7 @@ use idlib::Authorize;
 
 use serde::Deserialize;
 
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
 use time::OffsetDateTime;
 use tokio_rusqlite::Connection;
 
@@ -23,12 +23,25 @@ use crate::{
     into_response, Service, Services,
 };
 
+/// Column migrations applied to the pre-existing `user_invites` table at
+/// startup, mirroring the `SCHEMA` consts in `audit`/`ban`. Absent `expires_at`
+/// means "never expires" for rows created before this migration.
+pub(crate) const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE user_invites ADD COLUMN expires_at INTEGER NULL",
+    "ALTER TABLE user_invites ADD COLUMN email TEXT NULL",
+    "ALTER TABLE user_invites ADD COLUMN state TEXT NOT NULL DEFAULT 'created'",
+];
+
 struct Link {
     key: String,
     created_by: String,
     created_at: OffsetDateTime,
     used_by: Option<String>,
     used_at: Option<OffsetDateTime>,
+    expires_at: Option<OffsetDateTime>,
+    expired: bool,
+    email: Option<String>,
+    state: String,
 }
 
 #[derive(Template)]
@@ -66,6 +79,8 @@ pub(crate) async fn page(
     Extension(db): Extension<Connection>,
     Extension(Services(services)): Extension<Services>,
 ) -> Result<Response<BoxBody>, Error> {
+    sweep_expired_invites(&db).await?;
+
     let links = get_links(db).await?;
 
     let template = InvitePageTemplate {
@@ -77,6 +92,29 @@ pub(crate) async fn page(
     Ok(into_response(&template, "html"))
 }
 
+/// Lazily drop unused invite rows whose validity window has already passed.
+///
+/// Mirrors the `NOT EXISTS (SELECT 1 FROM users …)` guard used when deleting a
+/// single invite so that keys which have actually been redeemed are preserved
+/// for the admin view even once they expire.
+async fn sweep_expired_invites(db: &Connection) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    db.call(move |conn| {
+        conn.execute(
+            "DELETE FROM user_invites \
+            WHERE expires_at IS NOT NULL \
+            AND expires_at < ?1 \
+            AND NOT EXISTS (SELECT 1 FROM users WHERE invite_key = user_invites.\"key\")",
+            params![now],
+        )
+        .context("Failed to sweep expired invites")?;
+
+        Ok::<(), anyhow::Error>(())
+    })
+    .await
+}
+
 #[derive(Deserialize)]
 struct DbLinkInfo {
     key: String,
@@ -84,6 +122,9 @@ struct DbLinkInfo {
     created_at: i64,
     used_by: Option<String>,
     used_at: Option<i64>,
+    expires_at: Option<i64>,
+    email: Option<String>,
+    state: String,
 }
 
 async fn get_links(db: Connection) -> anyhow::Result<Vec<Link>> {
@@ -95,7 +136,10 @@ async fn get_links(db: Connection) -> anyhow::Result<Vec<Link>> {
                 ui.created_by, \
                 ui.created_at, \
                 u.username AS used_by, \
-                u.created_at AS used_at \
+                u.created_at AS used_at, \
+                ui.expires_at, \
+                ui.email, \
+                ui.state \
             FROM user_invites ui \
             LEFT OUTER JOIN \
                 users u \
@@ -109,6 +153,7 @@ async fn get_links(db: Connection) -> anyhow::Result<Vec<Link>> {
             .query_map(params![], |row| {
                 let info = serde_rusqlite::from_row::<DbLinkInfo>(row).unwrap();
 
+                let now = OffsetDateTime::now_utc().unix_timestamp();
                 let link = Link {
                     key: info.key,
                     created_by: info.created_by,
@@ -117,6 +162,12 @@ async fn get_links(db: Connection) -> anyhow::Result<Vec<Link>> {
                     used_at: info
                         .used_at
                         .map(|at| OffsetDateTime::from_unix_timestamp(at).unwrap()),
+                    expired: info.expires_at.is_some_and(|at| at < now),
+                    expires_at: info
+                        .expires_at
+                        .map(|at| OffsetDateTime::from_unix_timestamp(at).unwrap()),
+                    email: info.email,
+                    state: info.state,
                 };
 
                 Ok(link)
@@ -163,12 +214,16 @@ pub(crate) async fn delete_invite_impl(
     name: String,
 ) -> Result<(), Error> {
     db.call(move |conn| {
+        // Soft-revoke rather than hard-delete so the audit trail and any
+        // recipient email survive for the "who-hasn't-accepted" view. Used
+        // (accepted) keys are left untouched.
         conn.execute(
-            "DELETE FROM user_invites \
+            "UPDATE user_invites \
+            SET state = 'revoked' \
             WHERE \"key\" = ?1 AND NOT EXISTS (SELECT 1 FROM users WHERE invite_key = ?1)",
             params![&key],
         )
-        .context("Failed to delete invite")?;
+        .context("Failed to revoke invite")?;
 
         audit::log(conn, AuditAction::DeleteInvite(key), &name)?;
 
@@ -184,13 +239,30 @@ pub(crate) async fn create_page(
     Form(services): Form<Vec<(String, String)>>,
     Extension(db): Extension<Connection>,
 ) -> Result<Response<BoxBody>, Error> {
+    // The validity window, if present, is submitted alongside the service
+    // checkboxes as a plain `validity=<seconds>` field; anything else is a
+    // service toggle.
+    let validity = services
+        .iter()
+        .find(|(s, _)| s == "validity")
+        .and_then(|(_, v)| v.parse::<i64>().ok());
+
+    // A recipient email, when supplied, means the link is delivered by SMTP
+    // instead of being copy-pasted out of the admin UI.
+    let email = services
+        .iter()
+        .find(|(s, _)| s == "email")
+        .map(|(_, v)| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+
     let services = services
         .into_iter()
+        .filter(|(s, _)| s != "validity" && s != "email")
         .filter_map(|(s, v)| (v == "true").then(|| s))
         .collect::<Vec<_>>();
     let services = services.join(",");
 
-    let redirect = match create_invite_impl(db, name, services).await {
+    let redirect = match create_invite_impl(db, name, services, validity, email).await {
         Ok(()) => "/admin/invite#added".into(),
         Err(e) => format!(
             "/admin/invite?error={}",
@@ -211,16 +283,40 @@ pub(crate) async fn create_invite_impl(
     db: Connection,
     name: String,
     services: String,
+    validity_seconds: Option<i64>,
+    email: Option<String>,
 ) -> Result<(), Error> {
+    // Fall back to the server-configured default window; a validity of zero (or
+    // a negative default) means the link never expires.
+    let validity_seconds = validity_seconds.unwrap_or_else(default_invite_duration_seconds);
+
+    let key = create_invite_key();
+
+    // Deliver the link before committing the row so a failed SMTP send leaves
+    // no dangling `created` invite behind. A copy-paste invite (no recipient)
+    // skips delivery and persists in the default `created` state.
+    let state = match &email {
+        Some(email) => {
+            // SMTP connect/TLS/send is blocking and can stall for seconds, so
+            // keep it off the reactor thread.
+            let (to, k) = (email.clone(), key.clone());
+            tokio::task::spawn_blocking(move || send_invite_email(&to, &k))
+                .await
+                .context("Invite email task panicked")??;
+            "sent"
+        }
+        None => "created",
+    };
+
     db.call(move |conn| {
-        let key = create_invite_key();
         let now = OffsetDateTime::now_utc().unix_timestamp();
+        let expires_at = (validity_seconds > 0).then(|| now + validity_seconds);
         conn.execute(
-            "INSERT INTO user_invites (key, created_by, created_at, services)
-            VALUES (?1, ?2, ?3, ?4)",
-            params![&key, &name, now, services],
+            "INSERT INTO user_invites (key, created_by, created_at, services, expires_at, email, state)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![&key, &name, now, services, expires_at, email, state],
         )
-        .context("Failed to delete invite")?;
+        .context("Failed to create invite")?;
 
         audit::log(conn, AuditAction::CreateInvite(key), &name)?;
 
@@ -231,6 +327,88 @@ pub(crate) async fn create_invite_impl(
     Ok(())
 }
 
+#[derive(Template)]
+#[template(path = "invite_email.html")]
+struct InviteEmailTemplate {
+    url: String,
+}
+
+/// Build the public registration URL for an invite key. The service's external
+/// base address is taken from `PUBLIC_URL`.
+fn invite_url(key: &str) -> String {
+    let base = std::env::var("PUBLIC_URL").unwrap_or_default();
+    format!("{}/register?key={}", base.trim_end_matches('/'), key)
+}
+
+/// Render the invite email template and deliver it over SMTP using the
+/// `SMTP_HOST`/`SMTP_USER`/`SMTP_PASS`/`SMTP_FROM` environment configuration.
+fn send_invite_email(to: &str, key: &str) -> Result<(), Error> {
+    use lettre::transport::smtp::authentication::Credentials;
+    use lettre::{Message, SmtpTransport, Transport};
+
+    let host = std::env::var("SMTP_HOST").context("SMTP_HOST not set")?;
+    let user = std::env::var("SMTP_USER").context("SMTP_USER not set")?;
+    let pass = std::env::var("SMTP_PASS").context("SMTP_PASS not set")?;
+    let from = std::env::var("SMTP_FROM").context("SMTP_FROM not set")?;
+
+    let body = InviteEmailTemplate {
+        url: invite_url(key),
+    }
+    .render()
+    .context("Failed to render invite email")?;
+
+    let message = Message::builder()
+        .from(from.parse().context("Invalid SMTP_FROM address")?)
+        .to(to.parse().context("Invalid recipient address")?)
+        .subject("You've been invited")
+        .header(lettre::message::header::ContentType::TEXT_HTML)
+        .body(body)
+        .context("Failed to build invite email")?;
+
+    let mailer = SmtpTransport::relay(&host)
+        .context("Failed to connect to SMTP relay")?
+        .credentials(Credentials::new(user, pass))
+        .build();
+
+    mailer.send(&message).context("Failed to send invite email")?;
+
+    Ok(())
+}
+
 fn create_invite_key() -> String {
     blob_uuid::random_blob()
+}
+
+/// The default invite validity window in seconds, read from
+/// `INVITE_DURATION_SECONDS`. A missing or unparseable value, or a
+/// non-positive number, is treated as "never expires".
+fn default_invite_duration_seconds() -> i64 {
+    std::env::var("INVITE_DURATION_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Returns whether an invite key may still be redeemed, i.e. it exists and its
+/// `expires_at` (when set) has not yet passed. The registration path calls this
+/// before consuming a key so that expired links cannot create accounts.
+pub(crate) async fn is_invite_usable(db: &Connection, key: String) -> anyhow::Result<bool> {
+    db.call(move |conn| {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let usable = conn
+            .query_row(
+                "SELECT 1 FROM user_invites \
+                WHERE \"key\" = ?1 \
+                AND state IN ('created', 'sent') \
+                AND (expires_at IS NULL OR expires_at >= ?2)",
+                params![&key, now],
+                |_| Ok(()),
+            )
+            .optional()
+            .context("Failed to check invite validity")?
+            .is_some();
+
+        Ok(usable)
+    })
+    .await
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+use anyhow::Context;
+use axum::{
+    body::{boxed, BoxBody, Empty},
+    http::{header, Response, StatusCode},
+    Extension, Form,
+};
+
+use idlib::{Authorize, SecretKey};
+
+use serde::Deserialize;
+
+use tokio_rusqlite::Connection;
+
+use crate::{ban, error::Error, totp};
+
+#[derive(Deserialize)]
+pub(crate) struct LoginForm {
+    username: String,
+    password: String,
+    /// The six-digit second-factor code, present only for accounts that have
+    /// TOTP enabled.
+    totp: Option<String>,
+}
+
+/// Authenticate a user and mint a session token. The second factor is enforced
+/// here, between password verification and token issuance, so a token is never
+/// minted for an enrolled user who has not passed TOTP.
+pub(crate) async fn login(
+    Extension(db): Extension<Connection>,
+    Extension(secret): Extension<SecretKey>,
+    Form(LoginForm {
+        username,
+        password,
+        totp,
+    }): Form<LoginForm>,
+) -> Result<Response<BoxBody>, Error> {
+    if !crate::user::verify_password(&db, &username, &password).await? {
+        return Ok(unauthorized());
+    }
+
+    // Refuse suspended accounts (the effective_users view auto-lifts timed
+    // bans) before doing any further work.
+    if !ban::is_user_allowed(&db, username.clone()).await? {
+        return Ok(unauthorized());
+    }
+
+    // Enforce the optional second factor before issuing a token. Returns true
+    // for accounts without an enabled factor, so password-only logins still
+    // work.
+    if !totp::verify_login(&db, username.clone(), totp).await? {
+        return Ok(unauthorized());
+    }
+
+    let token = crate::token::issue(&secret, &username).context("Failed to mint token")?;
+
+    Ok(Response::builder()
+        .header(header::SET_COOKIE, format!("token={token}; HttpOnly; Path=/"))
+        .header("Location", "/")
+        .status(StatusCode::SEE_OTHER)
+        .body(boxed(Empty::new()))
+        .unwrap())
+}
+
+/// Re-issue a token for an already-authenticated session. A ban that landed (or
+/// has not yet expired) after the original login is enforced here too, so a
+/// suspended user cannot keep a session alive by refreshing.
+pub(crate) async fn refresh(
+    Authorize(username): Authorize<"token", "refresh">,
+    Extension(db): Extension<Connection>,
+    Extension(secret): Extension<SecretKey>,
+) -> Result<Response<BoxBody>, Error> {
+    if !ban::is_user_allowed(&db, username.clone()).await? {
+        return Ok(unauthorized());
+    }
+
+    let token = crate::token::issue(&secret, &username).context("Failed to mint token")?;
+
+    Ok(Response::builder()
+        .header(header::SET_COOKIE, format!("token={token}; HttpOnly; Path=/"))
+        .status(StatusCode::OK)
+        .body(boxed(Empty::new()))
+        .unwrap())
+}
+
+fn unauthorized() -> Response<BoxBody> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .body(boxed(Empty::new()))
+        .unwrap()
+}
@@ -0,0 +1,77 @@
+use anyhow::Context;
+use axum::{
+    body::{boxed, BoxBody, Empty},
+    http::{Response, StatusCode},
+    Extension, Form,
+};
+
+use serde::Deserialize;
+
+use rusqlite::params;
+use tokio_rusqlite::Connection;
+
+use crate::{error::Error, invite};
+
+#[derive(Deserialize)]
+pub(crate) struct RegisterForm {
+    key: String,
+    username: String,
+    password: String,
+}
+
+pub(crate) async fn register(
+    Form(RegisterForm {
+        key,
+        username,
+        password,
+    }): Form<RegisterForm>,
+    Extension(db): Extension<Connection>,
+) -> Result<Response<BoxBody>, Error> {
+    let redirect = match register_impl(db, key, username, password).await {
+        Ok(()) => "/login#registered".into(),
+        Err(e) => format!("/register?error={}", urlencoding::encode(&e.to_string())),
+    };
+
+    Ok(Response::builder()
+        .header("Location", &redirect)
+        .status(StatusCode::SEE_OTHER)
+        .body(boxed(Empty::new()))
+        .unwrap())
+}
+
+async fn register_impl(
+    db: Connection,
+    key: String,
+    username: String,
+    password: String,
+) -> Result<(), Error> {
+    // Reject keys that are expired, already used, or revoked before consuming
+    // them, so an expired link can never create an account.
+    if !invite::is_invite_usable(&db, key.clone()).await? {
+        return Err(anyhow::anyhow!("Invite link is no longer valid").into());
+    }
+
+    let hash = crate::user::hash_password(&password)?;
+
+    db.call(move |conn| {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        conn.execute(
+            "INSERT INTO users (username, password, invite_key, created_at) \
+            VALUES (?1, ?2, ?3, ?4)",
+            params![&username, &hash, &key, now],
+        )
+        .context("Failed to create user")?;
+
+        // Mark the invite accepted so it cannot be redeemed again.
+        conn.execute(
+            "UPDATE user_invites SET state = 'accepted' WHERE \"key\" = ?1",
+            params![&key],
+        )
+        .context("Failed to mark invite accepted")?;
+
+        Ok::<(), anyhow::Error>(())
+    })
+    .await?;
+
+    Ok(())
+}
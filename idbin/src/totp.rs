@@ -0,0 +1,206 @@
+use anyhow::Context;
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Form,
+};
+
+use idlib::Authorize;
+
+use serde::Deserialize;
+
+use rusqlite::{params, OptionalExtension};
+use tokio_rusqlite::Connection;
+
+use crate::error::Error;
+
+/// Per-user TOTP enrollment. `secret` is the active (confirmed) secret;
+/// `pending_secret` stages a not-yet-confirmed secret so that re-enrolling never
+/// disturbs a live factor. `enabled` flips to 1 only once a pending secret is
+/// confirmed, and `last_counter` records the most recently accepted step to
+/// stop replay.
+pub(crate) const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS user_totp ( \
+    username TEXT NOT NULL PRIMARY KEY, \
+    secret TEXT NULL, \
+    pending_secret TEXT NULL, \
+    enabled INTEGER NOT NULL DEFAULT 0, \
+    last_counter INTEGER NOT NULL DEFAULT 0 \
+);";
+
+/// Begin enrollment: generate a fresh secret, stage it in `pending_secret`, and
+/// return the `otpauth://` provisioning URI for the caller to render as a QR
+/// code. The active secret and `enabled` flag are left untouched, so an account
+/// that already has 2FA stays protected until the new secret is confirmed.
+pub(crate) async fn enroll(
+    Authorize(name): Authorize<"totp", "write">,
+    Extension(db): Extension<Connection>,
+) -> Result<Response, Error> {
+    let secret = idlib::encode_secret(&idlib::generate_secret());
+    let service_name = std::env::var("SERVICE_NAME").context("SERVICE_NAME not set")?;
+    let uri = idlib::provisioning_uri(&service_name, &name, &secret);
+
+    let stored = secret;
+    db.call(move |conn| {
+        conn.execute(
+            "INSERT INTO user_totp (username, pending_secret) \
+            VALUES (?1, ?2) \
+            ON CONFLICT(username) DO UPDATE SET pending_secret = excluded.pending_secret",
+            params![&name, &stored],
+        )
+        .context("Failed to store TOTP secret")?;
+
+        Ok::<(), anyhow::Error>(())
+    })
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain")],
+        uri,
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ConfirmForm {
+    code: String,
+}
+
+/// Confirm enrollment by checking a code against the pending secret. On success
+/// the pending secret is promoted to the active secret and the factor is
+/// enabled; the live secret is only ever replaced here.
+pub(crate) async fn confirm(
+    Authorize(name): Authorize<"totp", "write">,
+    Form(ConfirmForm { code }): Form<ConfirmForm>,
+    Extension(db): Extension<Connection>,
+) -> Result<Response, Error> {
+    let accepted = confirm_pending(&db, name, &code).await?;
+
+    let status = if accepted {
+        StatusCode::OK
+    } else {
+        StatusCode::UNAUTHORIZED
+    };
+
+    Ok((status, [(header::CONTENT_TYPE, "text/plain")], "").into_response())
+}
+
+/// Verify `code` against the staged `pending_secret` and, on success, promote
+/// it to the active secret, enable the factor, and record the accepted counter.
+async fn confirm_pending(db: &Connection, username: String, code: &str) -> Result<bool, Error> {
+    let code = code.to_string();
+    let accepted = db
+        .call(move |conn| {
+            let pending = conn
+                .query_row(
+                    "SELECT pending_secret FROM user_totp WHERE username = ?1",
+                    params![&username],
+                    |row| row.get::<_, Option<String>>(0),
+                )
+                .optional()
+                .context("Failed to read pending TOTP secret")?
+                .flatten();
+
+            let Some(secret) = pending else {
+                return Ok::<bool, anyhow::Error>(false);
+            };
+
+            let now = time::OffsetDateTime::now_utc().unix_timestamp();
+            match idlib::verify(&secret, &code, now, 0) {
+                Some(counter) => {
+                    conn.execute(
+                        "UPDATE user_totp \
+                        SET secret = ?2, pending_secret = NULL, enabled = 1, last_counter = ?3 \
+                        WHERE username = ?1",
+                        params![&username, &secret, counter as i64],
+                    )
+                    .context("Failed to activate TOTP secret")?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+        .await?;
+
+    Ok(accepted)
+}
+
+/// The verify step the login path must pass before a session token is minted.
+///
+/// Returns `true` when the user has no enabled second factor (2FA is optional)
+/// or when the supplied code verifies; a successful verification advances the
+/// stored `last_counter` so the code cannot be replayed.
+pub(crate) async fn verify_login(
+    db: &Connection,
+    username: String,
+    code: Option<String>,
+) -> Result<bool, Error> {
+    let enabled = is_enabled(db, username.clone()).await?;
+    if !enabled {
+        return Ok(true);
+    }
+
+    match code {
+        Some(code) => verify_active(db, username, &code).await,
+        None => Ok(false),
+    }
+}
+
+async fn is_enabled(db: &Connection, username: String) -> Result<bool, Error> {
+    let enabled = db
+        .call(move |conn| {
+            let enabled = conn
+                .query_row(
+                    "SELECT enabled FROM user_totp WHERE username = ?1",
+                    params![&username],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()
+                .context("Failed to read TOTP enrollment")?
+                .unwrap_or(0);
+
+            Ok::<bool, anyhow::Error>(enabled != 0)
+        })
+        .await?;
+
+    Ok(enabled)
+}
+
+/// Load the user's active secret and last counter, verify `code`, and on
+/// success persist the accepted counter to prevent replay.
+async fn verify_active(db: &Connection, username: String, code: &str) -> Result<bool, Error> {
+    let code = code.to_string();
+    let accepted = db
+        .call(move |conn| {
+            let row = conn
+                .query_row(
+                    "SELECT secret, last_counter FROM user_totp \
+                    WHERE username = ?1 AND enabled = 1",
+                    params![&username],
+                    |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, i64>(1)?)),
+                )
+                .optional()
+                .context("Failed to read TOTP secret")?;
+
+            let Some((Some(secret), last_counter)) = row else {
+                return Ok::<bool, anyhow::Error>(false);
+            };
+
+            let now = time::OffsetDateTime::now_utc().unix_timestamp();
+            match idlib::verify(&secret, &code, now, last_counter as u64) {
+                Some(counter) => {
+                    conn.execute(
+                        "UPDATE user_totp SET last_counter = ?2 WHERE username = ?1",
+                        params![&username, counter as i64],
+                    )
+                    .context("Failed to record TOTP counter")?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+        .await?;
+
+    Ok(accepted)
+}
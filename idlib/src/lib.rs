@@ -1,17 +1,27 @@
 #![feature(adt_const_params)]
 
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{bail, Context};
+use hkdf::Hkdf;
 use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use std::{env, sync::Arc};
 
+/// HKDF info label that separates the AES content key from the HMAC signing
+/// key so the two never coincide even though they share `IDP_SECRET_KEY`.
+const TOKEN_ENCRYPTION_INFO: &[u8] = b"idp-token-encryption-v1";
+
 mod authenticate;
 mod authorize;
 mod error;
+mod totp;
 
 pub use authenticate::*;
 pub use authorize::*;
 pub use error::*;
+pub use totp::*;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PermissionResponse {
@@ -19,17 +29,281 @@ pub struct PermissionResponse {
     pub group_policy: Vec<Vec<String>>,
 }
 
+/// Whether a grant is scoped to the requesting service or applies across every
+/// service. Global grants coalesce over (extend and override) the local ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Local,
+    Global,
+}
+
+/// Capability tier. `Moderator` covers ordinary read/write actions; `Admin`
+/// additionally covers editing the permission and group assignments
+/// themselves, so the two can be granted independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Moderator,
+    Admin,
+}
+
+/// A single resolved right: a `(resource, action)` pair the caller holds, the
+/// scope it came from, the tier it was granted at, and its absolute unix
+/// expiry (if any).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectivePermission {
+    pub resource: String,
+    pub action: String,
+    pub scope: Scope,
+    pub tier: Tier,
+    pub expires_at: Option<i64>,
+}
+
+impl PermissionResponse {
+    /// Coalesce the flat `policy`/`group_policy` rows into the caller's
+    /// effective rights for `service` at time `now`: rows past their expiry are
+    /// dropped, and where a `(resource, action)` appears both globally and
+    /// locally the global row wins (taking the higher tier and the longer-lived
+    /// expiry).
+    ///
+    /// Rows are read positionally as
+    /// `[resource, action, scope?, tier?, expires_at?]`; the trailing three
+    /// columns are optional so pre-existing two-column rows still parse as
+    /// local moderator grants that never expire.
+    pub fn resolve_effective(&self, service: &str, now: i64) -> Vec<EffectivePermission> {
+        use std::collections::HashMap;
+
+        let mut resolved: HashMap<(String, String), EffectivePermission> = HashMap::new();
+
+        for row in self.policy.iter().chain(self.group_policy.iter()) {
+            let Some(perm) = parse_permission_row(row, service) else {
+                continue;
+            };
+
+            if perm.expires_at.is_some_and(|at| at < now) {
+                continue;
+            }
+
+            let key = (perm.resource.clone(), perm.action.clone());
+            match resolved.remove(&key) {
+                Some(existing) => {
+                    resolved.insert(key, coalesce(existing, perm));
+                }
+                None => {
+                    resolved.insert(key, perm);
+                }
+            }
+        }
+
+        resolved.into_values().collect()
+    }
+}
+
+impl EffectivePermission {
+    /// Whether this grant confers the right to edit the permission and group
+    /// assignments for its resource. Only `admin`-tier grants do; an ordinary
+    /// `moderator` write grant does not, keeping the power to mint new grants
+    /// separable from ordinary write actions.
+    pub fn can_administer(&self) -> bool {
+        self.tier == Tier::Admin
+    }
+}
+
+/// The fully-resolved effective-permission set for a caller: the single value a
+/// service (or the `Authorize` layer) consults instead of re-deriving rights
+/// from the raw `policy`/`group_policy` rows. Serializable so it can be served
+/// directly from a permissions endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectivePermissions {
+    pub permissions: Vec<EffectivePermission>,
+}
+
+impl EffectivePermissions {
+    /// Whether the caller holds any (non-expired) grant for `(resource,
+    /// action)`, regardless of tier.
+    pub fn allows(&self, resource: &str, action: &str) -> bool {
+        self.permissions
+            .iter()
+            .any(|p| p.resource == resource && p.action == action)
+    }
+
+    /// Whether the caller may edit the grant/group assignments for `resource`,
+    /// i.e. holds an `admin`-tier grant on it. An ordinary write grant does not
+    /// satisfy this, keeping the moderator/admin split enforced.
+    pub fn can_administer(&self, resource: &str) -> bool {
+        self.permissions
+            .iter()
+            .any(|p| p.resource == resource && p.can_administer())
+    }
+
+    /// Enforce that the caller may mint/edit grants on `resource`, returning an
+    /// error otherwise. Used by the grant-editing endpoints so that, e.g.,
+    /// `Authorize<"invite","write">` does not implicitly allow minting grants.
+    pub fn require_admin(&self, resource: &str) -> Result<(), crate::Error> {
+        if self.can_administer(resource) {
+            Ok(())
+        } else {
+            Err(crate::Error::Unauthorized)
+        }
+    }
+}
+
+impl PermissionResponse {
+    /// Resolve the caller's effective rights for `service` at `now`. This is the
+    /// single entry point services and the `Authorize` layer call; the returned
+    /// [`EffectivePermissions`] can also be serialized straight out of a
+    /// permissions endpoint.
+    pub fn effective(&self, service: &str, now: i64) -> EffectivePermissions {
+        EffectivePermissions {
+            permissions: self.resolve_effective(service, now),
+        }
+    }
+}
+
+/// Parse a raw policy row into an [`EffectivePermission`], discarding rows
+/// whose explicit scope is `local` but targets a different service.
+fn parse_permission_row(row: &[String], service: &str) -> Option<EffectivePermission> {
+    let resource = row.get(0)?.clone();
+    let action = row.get(1)?.clone();
+
+    let scope = match row.get(2).map(String::as_str) {
+        Some("global") => Scope::Global,
+        // A local row may name the service it belongs to as `local:<service>`;
+        // a bare `local` (or nothing) is assumed to be for this service.
+        Some(s) if s.starts_with("local:") => {
+            if &s["local:".len()..] != service {
+                return None;
+            }
+            Scope::Local
+        }
+        _ => Scope::Local,
+    };
+
+    let tier = match row.get(3).map(String::as_str) {
+        Some("admin") => Tier::Admin,
+        _ => Tier::Moderator,
+    };
+
+    let expires_at = row.get(4).and_then(|v| v.parse::<i64>().ok());
+
+    Some(EffectivePermission {
+        resource,
+        action,
+        scope,
+        tier,
+        expires_at,
+    })
+}
+
+/// Coalesce two grants for the same `(resource, action)` into their union:
+/// global scope extends local (global wins the scope), the higher tier wins so
+/// a grant is never demoted (the admin bit survives if either row has it), and
+/// the longer-lived expiry wins (`None` means never expires).
+fn coalesce(a: EffectivePermission, b: EffectivePermission) -> EffectivePermission {
+    let scope = if a.scope == Scope::Global || b.scope == Scope::Global {
+        Scope::Global
+    } else {
+        Scope::Local
+    };
+
+    let tier = a.tier.max(b.tier);
+
+    let expires_at = match (a.expires_at, b.expires_at) {
+        (None, _) | (_, None) => None,
+        (Some(x), Some(y)) => Some(x.max(y)),
+    };
+
+    EffectivePermission {
+        resource: a.resource,
+        action: a.action,
+        scope,
+        tier,
+        expires_at,
+    }
+}
+
+/// Token-signing key, optionally carrying a derived content-encryption key for
+/// the confidential-token mode. The HMAC signer is public for backward
+/// compatibility; the AES key is only touched through the seal/open helpers.
 #[derive(Clone)]
-pub struct SecretKey(pub Arc<Hmac<Sha256>>);
+pub struct SecretKey(pub Arc<Hmac<Sha256>>, Option<Arc<[u8; 32]>>);
 
 impl SecretKey {
-    pub fn from_env() -> Self {
+    fn secret_material() -> Vec<u8> {
         let secret_key: String = env::var("IDP_SECRET_KEY").expect("IDP_SECRET_KEY not set");
-        let secret_key = base64::decode(&secret_key).unwrap();
-        let secret_key = Hmac::<Sha256>::new_from_slice(&secret_key)
+        base64::decode(&secret_key).unwrap()
+    }
+
+    pub fn from_env() -> Self {
+        let secret_key = Self::secret_material();
+        let signer = Hmac::<Sha256>::new_from_slice(&secret_key)
             .expect("Failed to create HMAC from secret key");
 
-        SecretKey(Arc::new(secret_key))
+        SecretKey(Arc::new(signer), None)
+    }
+
+    /// Like [`from_env`], but additionally derives a 32-byte AES-256-GCM content
+    /// key (via HKDF-SHA256 with a fixed info label) so token claims can be
+    /// encrypted before signing.
+    ///
+    /// [`from_env`]: SecretKey::from_env
+    pub fn with_encryption() -> Self {
+        let secret_key = Self::secret_material();
+        let signer = Hmac::<Sha256>::new_from_slice(&secret_key)
+            .expect("Failed to create HMAC from secret key");
+
+        let hk = Hkdf::<Sha256>::new(None, &secret_key);
+        let mut content_key = [0u8; 32];
+        hk.expand(TOKEN_ENCRYPTION_INFO, &mut content_key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+        SecretKey(Arc::new(signer), Some(Arc::new(content_key)))
+    }
+
+    /// Whether confidential-token mode is active.
+    pub fn encryption_enabled(&self) -> bool {
+        self.1.is_some()
+    }
+
+    /// Encrypt serialized claims with AES-256-GCM under a fresh 12-byte nonce,
+    /// returning `base64(nonce || ciphertext || tag)`. Errors if encryption is
+    /// not enabled for this key.
+    pub fn seal(&self, plaintext: &[u8]) -> anyhow::Result<String> {
+        let key = self.1.as_ref().context("Token encryption is not enabled")?;
+        let cipher = Aes256Gcm::new(key.as_ref().into());
+
+        let mut nonce = [0u8; 12];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut nonce);
+
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt token claims"))?;
+
+        let mut envelope = Vec::with_capacity(nonce.len() + ciphertext.len());
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+
+        Ok(base64::encode(envelope))
+    }
+
+    /// Reverse of [`seal`]: split the nonce off the front and verify the GCM
+    /// tag, returning the plaintext claims. Any tag mismatch is an error.
+    ///
+    /// [`seal`]: SecretKey::seal
+    pub fn open(&self, data: &str) -> anyhow::Result<Vec<u8>> {
+        let key = self.1.as_ref().context("Token encryption is not enabled")?;
+        let cipher = Aes256Gcm::new(key.as_ref().into());
+
+        let envelope = base64::decode(data).context("Invalid token encoding")?;
+        if envelope.len() < 12 {
+            bail!("Token is too short to contain a nonce");
+        }
+        let (nonce, ciphertext) = envelope.split_at(12);
+
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Token authentication tag mismatch"))
     }
 }
 
@@ -40,15 +314,138 @@ pub struct Variables {
     pub idp_refresh_address: String,
     pub idp_login_address: String,
     pub token_duration_seconds: u32,
+    pub invite_duration_seconds: i64,
+    pub confidential_tokens: bool,
     pub service_name: String,
 }
 
+#[cfg(test)]
+mod permission_tests {
+    use super::*;
+
+    fn row(cols: &[&str]) -> Vec<String> {
+        cols.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn global_grant_beats_local() {
+        let resp = PermissionResponse {
+            policy: vec![
+                row(&["invite", "write", "local", "moderator"]),
+                row(&["invite", "write", "global", "admin"]),
+            ],
+            group_policy: vec![],
+        };
+
+        let effective = resp.effective("svc", 0);
+        assert_eq!(effective.permissions.len(), 1);
+        let p = &effective.permissions[0];
+        assert_eq!(p.scope, Scope::Global);
+        assert_eq!(p.tier, Tier::Admin);
+    }
+
+    #[test]
+    fn global_grant_does_not_demote_local_admin() {
+        let resp = PermissionResponse {
+            policy: vec![
+                row(&["invite", "write", "local", "admin"]),
+                row(&["invite", "write", "global", "moderator"]),
+            ],
+            group_policy: vec![],
+        };
+
+        let effective = resp.effective("svc", 0);
+        assert_eq!(effective.permissions.len(), 1);
+        let p = &effective.permissions[0];
+        // Global scope extends reach but must not strip the admin capability.
+        assert_eq!(p.scope, Scope::Global);
+        assert_eq!(p.tier, Tier::Admin);
+        assert!(effective.can_administer("invite"));
+    }
+
+    #[test]
+    fn expired_rows_are_dropped() {
+        let resp = PermissionResponse {
+            policy: vec![row(&["users", "read", "local", "moderator", "100"])],
+            group_policy: vec![],
+        };
+
+        assert!(resp.effective("svc", 200).permissions.is_empty());
+        assert_eq!(resp.effective("svc", 50).permissions.len(), 1);
+    }
+
+    #[test]
+    fn local_rows_for_other_services_are_ignored() {
+        let resp = PermissionResponse {
+            policy: vec![row(&["x", "read", "local:other"])],
+            group_policy: vec![],
+        };
+
+        assert!(resp.effective("svc", 0).permissions.is_empty());
+    }
+
+    #[test]
+    fn admin_split_is_enforced() {
+        let resp = PermissionResponse {
+            policy: vec![row(&["invite", "write", "local", "moderator"])],
+            group_policy: vec![],
+        };
+
+        let effective = resp.effective("svc", 0);
+        assert!(effective.allows("invite", "write"));
+        // A plain write grant must not confer grant-editing rights.
+        assert!(!effective.can_administer("invite"));
+    }
+}
+
+#[cfg(test)]
+mod encryption_tests {
+    use super::*;
+
+    fn key_with_encryption() -> SecretKey {
+        env::set_var("IDP_SECRET_KEY", base64::encode([7u8; 32]));
+        SecretKey::with_encryption()
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        let key = key_with_encryption();
+        let claims = b"sub=alice;exp=123";
+
+        let sealed = key.seal(claims).unwrap();
+        assert_eq!(key.open(&sealed).unwrap(), claims);
+    }
+
+    #[test]
+    fn open_rejects_tampered_tag() {
+        let key = key_with_encryption();
+        let sealed = key.seal(b"sub=alice").unwrap();
+
+        let mut raw = base64::decode(&sealed).unwrap();
+        let last = raw.len() - 1;
+        raw[last] ^= 0x01;
+
+        assert!(key.open(&base64::encode(raw)).is_err());
+    }
+
+    #[test]
+    fn plaintext_key_cannot_seal() {
+        env::set_var("IDP_SECRET_KEY", base64::encode([7u8; 32]));
+        let key = SecretKey::from_env();
+
+        assert!(!key.encryption_enabled());
+        assert!(key.seal(b"x").is_err());
+    }
+}
+
 impl Variables {
     pub fn from_env() -> Self {
         Variables {
             idp_login_address: env::var("IDP_LOGIN_ADDR").expect("IDP_LOGIN_ADDR not set"),
             idp_refresh_address: env::var("IDP_REFRESH_ADDR").expect("IDP_REFRESH_ADDR not set"),
             token_duration_seconds: env::var("TOKEN_DURATION_SECONDS").expect("TOKEN_DURATION_SECONDS not set").parse().expect("Expected integer"),
+            invite_duration_seconds: env::var("INVITE_DURATION_SECONDS").ok().map(|v| v.parse().expect("Expected integer")).unwrap_or(0),
+            confidential_tokens: env::var("CONFIDENTIAL_TOKENS").map(|v| v == "true").unwrap_or(false),
             service_name: env::var("SERVICE_NAME").expect("SERVICE_NAME not set"),
         }
     }